@@ -8,6 +8,7 @@ use regex::Regex;
 use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
 
 use std::borrow::Cow;
+use std::collections::HashSet;
 
 pub use self::string::take_lines;
 
@@ -65,7 +66,253 @@ pub fn id_from_content(content: &str) -> String {
     normalize_id(trimmed)
 }
 
-fn adjust_links<'a>(event: Event<'a>, with_base: &str) -> Event<'a> {
+/// Tracks anchor IDs that have already been emitted on a page so that two
+/// headings with the same text (e.g. two "Examples" sections) don't
+/// collide, which would otherwise leave in-page links to the second one
+/// resolving to the first. Modeled on rustdoc's `IdMap`.
+#[derive(Default)]
+pub struct IdMap {
+    used_ids: HashSet<String>,
+}
+
+impl IdMap {
+    pub fn new() -> Self {
+        IdMap::default()
+    }
+
+    /// Computes the anchor ID for some heading content, returning it
+    /// unchanged the first time it's seen. On a collision, tries an
+    /// incrementing `-1`, `-2`, ... suffix until it lands on one that
+    /// isn't already in use by *any* other heading on the page (not just
+    /// ones with the same base), so e.g. a literal "Foo-1" heading can't
+    /// collide with the anchor generated for a second "Foo" heading.
+    pub fn add(&mut self, content: &str) -> String {
+        let mut base = id_from_content(content);
+        if base.is_empty() {
+            // An empty base (e.g. a heading made up entirely of emoji)
+            // would collide with every other empty heading; fall back to
+            // something stable instead.
+            base = "a".to_string();
+        }
+
+        let mut candidate = base.clone();
+        let mut suffix = 1;
+        while self.used_ids.contains(&candidate) {
+            candidate = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+
+        self.used_ids.insert(candidate.clone());
+        candidate
+    }
+}
+
+/// A single entry in a page's in-page table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocItem {
+    pub level: u32,
+    pub name: String,
+    pub id: String,
+    pub children: Vec<TocItem>,
+}
+
+/// A nested table of contents for a single page, built by [`TocBuilder`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Toc {
+    pub items: Vec<TocItem>,
+}
+
+/// Builds a nested [`Toc`] from a page's heading stream, so the HTML
+/// renderer can offer a `{{#toc}}`-style "On this page" navigation list.
+/// Anchor IDs are taken from the `IdMap` passed to [`TocBuilder::new`], so
+/// they match any other IDs drawn from that same map; the `render_markdown*`
+/// functions do not use an `IdMap`.
+pub struct TocBuilder<'id> {
+    id_map: &'id mut IdMap,
+    top: Vec<TocItem>,
+    // Stack of currently open (level, siblings) frames below `top`.
+    stack: Vec<(u32, Vec<TocItem>)>,
+    current_level: Option<u32>,
+    current_text: String,
+}
+
+impl<'id> TocBuilder<'id> {
+    pub fn new(id_map: &'id mut IdMap) -> Self {
+        TocBuilder {
+            id_map,
+            top: Vec::new(),
+            stack: Vec::new(),
+            current_level: None,
+            current_text: String::new(),
+        }
+    }
+
+    /// Feeds a single markdown event into the builder. Only heading
+    /// boundaries and the text between them are inspected; everything
+    /// else is ignored.
+    pub fn consume(&mut self, event: &Event<'_>) {
+        match event {
+            Event::Start(Tag::Heading(level)) => {
+                self.current_level = Some(*level);
+                self.current_text.clear();
+            }
+            Event::End(Tag::Heading(level)) => {
+                let name = self.current_text.trim().to_string();
+                let id = self.id_map.add(&name);
+                self.push(*level, name, id);
+                self.current_level = None;
+            }
+            Event::Text(text) | Event::Code(text) if self.current_level.is_some() => {
+                self.current_text.push_str(text);
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, level: u32, name: String, id: String) {
+        // Close every open frame deeper than this level, folding its
+        // items into its parent's last item as that item's children.
+        while let Some(&(top_level, _)) = self.stack.last() {
+            if top_level > level {
+                let (_, children) = self.stack.pop().unwrap();
+                self.attach_children(children);
+            } else {
+                break;
+            }
+        }
+
+        match self.stack.last_mut() {
+            Some((top_level, items)) if *top_level == level => {
+                items.push(TocItem { level, name, id, children: Vec::new() });
+            }
+            _ => {
+                // First heading at this level: synthesize any levels that
+                // were skipped over (e.g. an H2 followed directly by an
+                // H4) so the tree still nests instead of panicking.
+                let parent_level = self.stack.last().map_or(0, |&(l, _)| l);
+                for synthetic in (parent_level + 1)..level {
+                    self.stack.push((synthetic, Vec::new()));
+                }
+                self.stack.push((level, vec![TocItem { level, name, id, children: Vec::new() }]));
+            }
+        }
+    }
+
+    fn attach_children(&mut self, children: Vec<TocItem>) {
+        match self.stack.last_mut() {
+            Some((_, items)) => match items.last_mut() {
+                Some(last) => last.children = children,
+                // No real heading owns this synthetic level; surface its
+                // items directly so nothing is lost.
+                None => items.extend(children),
+            },
+            None => self.top.extend(children),
+        }
+    }
+
+    /// Finishes the page and returns the nested table of contents.
+    pub fn into_toc(mut self) -> Toc {
+        while let Some((_, children)) = self.stack.pop() {
+            self.attach_children(children);
+        }
+        Toc { items: self.top }
+    }
+}
+
+/// A relative link or image destination rewritten by `adjust_links` while
+/// rendering a page, recorded so the book-wide link checker can
+/// cross-reference it once every page has been rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkTarget {
+    /// The rewritten `.html` path as written into the page: the link's own
+    /// relative path, prefixed with the render's `base` if one was given.
+    pub path: String,
+    /// The `#anchor` fragment, if the link pointed at one.
+    pub fragment: Option<String>,
+}
+
+/// Collects every relative link/image target rewritten on a single page.
+#[derive(Debug, Default)]
+pub struct LinkCollector {
+    pub targets: Vec<LinkTarget>,
+}
+
+impl LinkCollector {
+    pub fn new() -> Self {
+        LinkCollector::default()
+    }
+
+    fn record(&mut self, dest: &str) {
+        let (path, fragment) = match dest.find('#') {
+            Some(idx) => (dest[..idx].to_string(), Some(dest[idx + 1..].to_string())),
+            None => (dest.to_string(), None),
+        };
+
+        if path.is_empty() {
+            // A same-page anchor like `#section`: there's no separate
+            // page to look up, so it's out of scope for the page-level
+            // checks `check_links` does.
+            return;
+        }
+
+        self.targets.push(LinkTarget { path, fragment });
+    }
+}
+
+/// One dangling reference found by [`check_links`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrokenLink {
+    /// The link's target page doesn't exist in the rendered book.
+    MissingPage { from: String, target: String },
+    /// The target page exists, but not the anchor within it.
+    MissingFragment {
+        from: String,
+        target: String,
+        fragment: String,
+    },
+}
+
+/// Cross-checks every target recorded by a page's `LinkCollector` against
+/// the set of output pages in `pages` and the per-page anchor sets in
+/// `anchors` (such as the IDs an `IdMap` produced for each page), and
+/// returns every dangling reference found. It does not log anything or
+/// fail the build itself, and no strict mode is read from `book.toml`.
+pub fn check_links<'a>(
+    links: impl IntoIterator<Item = (&'a str, &'a LinkTarget)>,
+    pages: &std::collections::HashSet<String>,
+    anchors: &std::collections::HashMap<String, std::collections::HashSet<String>>,
+) -> Vec<BrokenLink> {
+    let mut broken = Vec::new();
+    for (from, target) in links {
+        if !pages.contains(&target.path) {
+            broken.push(BrokenLink::MissingPage {
+                from: from.to_string(),
+                target: target.path.clone(),
+            });
+            continue;
+        }
+
+        if let Some(fragment) = &target.fragment {
+            let has_fragment = anchors
+                .get(&target.path)
+                .map_or(false, |page_anchors| page_anchors.contains(fragment));
+            if !has_fragment {
+                broken.push(BrokenLink::MissingFragment {
+                    from: from.to_string(),
+                    target: target.path.clone(),
+                    fragment: fragment.clone(),
+                });
+            }
+        }
+    }
+    broken
+}
+
+fn adjust_links<'a>(
+    event: Event<'a>,
+    with_base: &str,
+    mut link_collector: Option<&mut LinkCollector>,
+) -> Event<'a> {
     lazy_static! {
         static ref SCHEME_LINK: Regex = Regex::new(r"^[a-z][a-z0-9+.-]*:").unwrap();
         static ref MD_LINK: Regex = Regex::new(r"(?P<link>.*)\.md(?P<anchor>#.*)?").unwrap();
@@ -95,12 +342,36 @@ fn adjust_links<'a>(event: Event<'a>, with_base: &str) -> Event<'a> {
         dest
     }
 
+    // Only a genuine `.md` -> `.html` rewrite is a cross-reference to
+    // another page; anything else that comes out of `fix()` unscathed
+    // (a same-page `#anchor`, an image, a download link, ...) isn't a
+    // page reference `check_links` can meaningfully validate.
+    fn is_md_link(dest: &str) -> bool {
+        !SCHEME_LINK.is_match(dest) && MD_LINK.is_match(dest)
+    }
+
+    fn record(fixed_dest: &str, link_collector: &mut Option<&mut LinkCollector>) {
+        if let Some(collector) = link_collector {
+            collector.record(fixed_dest);
+        }
+    }
+
     match event {
         Event::Start(Tag::Link(link_type, dest, title)) => {
-            Event::Start(Tag::Link(link_type, fix(dest, with_base), title))
+            let was_md_link = is_md_link(&dest);
+            let dest = fix(dest, with_base);
+            if was_md_link {
+                record(&dest, &mut link_collector);
+            }
+            Event::Start(Tag::Link(link_type, dest, title))
         }
         Event::Start(Tag::Image(link_type, dest, title)) => {
-            Event::Start(Tag::Image(link_type, fix(dest, with_base), title))
+            let was_md_link = is_md_link(&dest);
+            let dest = fix(dest, with_base);
+            if was_md_link {
+                record(&dest, &mut link_collector);
+            }
+            Event::Start(Tag::Image(link_type, dest, title))
         }
         _ => event,
     }
@@ -121,28 +392,267 @@ pub fn new_cmark_parser(text: &str) -> Parser<'_> {
 }
 
 pub fn render_markdown_with_base(text: &str, curly_quotes: bool, base: &str) -> String {
+    let typography = if curly_quotes { Some("en") } else { None };
+    render_markdown_with_typography(text, typography, base)
+}
+
+/// Like [`render_markdown_with_base`], but rather than a plain curly-quotes
+/// toggle, selects a full [`Typography`] pass by the locale code passed in
+/// (e.g. `"fr"`); the book config is not read here. `None` disables typography
+/// entirely; an unrecognised or `Some("en")` locale keeps today's English
+/// curly-quote behavior.
+pub fn render_markdown_with_typography(text: &str, typography: Option<&str>, base: &str) -> String {
+    render_markdown_with_heading_offset(text, typography, base, 0)
+}
+
+/// Like [`render_markdown_with_typography`], but additionally shifts every
+/// heading in `text` down by `heading_offset` levels (clamped at H6). A
+/// chapter that `{{#include}}`s another file can use this to demote that
+/// file's `#` titles to `###`, so the included content doesn't introduce a
+/// second, competing H1 into the page.
+pub fn render_markdown_with_heading_offset(
+    text: &str,
+    typography: Option<&str>,
+    base: &str,
+    heading_offset: u32,
+) -> String {
+    render_markdown_checking_links(text, typography, base, heading_offset, None)
+}
+
+/// Like [`render_markdown_with_heading_offset`], but also records every
+/// relative link/image target rewritten while rendering `text` into
+/// `link_collector`, for later cross-checking by [`check_links`].
+pub fn render_markdown_checking_links(
+    text: &str,
+    typography: Option<&str>,
+    base: &str,
+    heading_offset: u32,
+    mut link_collector: Option<&mut LinkCollector>,
+) -> String {
     let mut s = String::with_capacity(text.len() * 3 / 2);
     let p = new_cmark_parser(text);
-    let mut converter = EventQuoteConverter::new(curly_quotes);
+    let mut converter = TypographyConverter::new(typography);
     let events = p
         .map(clean_codeblock_headers)
-        .map(|event| adjust_links(event, base))
+        .map(|event| adjust_links(event, base, link_collector.as_mut().map(|lc| &mut **lc)))
+        .map(move |event| offset_heading(event, heading_offset))
         .map(|event| converter.convert(event));
 
     html::push_html(&mut s, events);
     s
 }
 
-struct EventQuoteConverter {
+/// Shifts a single heading event down by `offset` levels, clamping at H6.
+fn offset_heading(event: Event<'_>, offset: u32) -> Event<'_> {
+    fn shift(level: u32, offset: u32) -> u32 {
+        (level + offset).min(6)
+    }
+
+    match event {
+        Event::Start(Tag::Heading(level)) => Event::Start(Tag::Heading(shift(level, offset))),
+        Event::End(Tag::Heading(level)) => Event::End(Tag::Heading(shift(level, offset))),
+        _ => event,
+    }
+}
+
+/// Renders `text` to a length-limited, well-formed HTML snippet, for use
+/// as a search-index preview. Naively truncating rendered HTML would cut
+/// tags in half; this instead stops consuming markdown once `max_chars`
+/// worth of visible text has been emitted, and closes every tag still
+/// open at that point.
+pub fn render_markdown_summary(text: &str, max_chars: usize) -> String {
+    let mut writer = HtmlWithLimit::new(max_chars);
+    let mut parser = new_cmark_parser(text).map(clean_codeblock_headers);
+
+    while let Some(event) = parser.next() {
+        if writer.is_stopped() {
+            break;
+        }
+        writer.consume(event);
+    }
+
+    writer.finish()
+}
+
+/// An HTML writer that stops once a budget of visible characters has been
+/// emitted, closing every tag still open so the result stays well-formed.
+/// Modeled on rustdoc's `HtmlWithLimit`.
+struct HtmlWithLimit {
+    buf: String,
+    remaining: usize,
+    stopped: bool,
+    open_tags: Vec<&'static str>,
+}
+
+impl HtmlWithLimit {
+    fn new(limit: usize) -> Self {
+        HtmlWithLimit {
+            buf: String::new(),
+            remaining: limit,
+            stopped: false,
+            open_tags: Vec::new(),
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    fn finish(mut self) -> String {
+        for tag in self.open_tags.drain(..).rev() {
+            self.buf.push_str("</");
+            self.buf.push_str(tag);
+            self.buf.push('>');
+        }
+        self.buf
+    }
+
+    fn open_tag(&mut self, tag: &'static str) {
+        self.buf.push('<');
+        self.buf.push_str(tag);
+        self.buf.push('>');
+        self.open_tags.push(tag);
+    }
+
+    fn close_tag(&mut self) {
+        if let Some(tag) = self.open_tags.pop() {
+            self.buf.push_str("</");
+            self.buf.push_str(tag);
+            self.buf.push('>');
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.remaining == 0 {
+            self.stopped = true;
+            return;
+        }
+
+        let mut taken = 0;
+        for ch in text.chars() {
+            if taken >= self.remaining {
+                self.stopped = true;
+                break;
+            }
+            self.buf.push(ch);
+            taken += 1;
+        }
+        self.remaining -= taken;
+    }
+
+    fn consume(&mut self, event: Event<'_>) {
+        if self.stopped {
+            return;
+        }
+
+        match event {
+            Event::Start(tag) => {
+                if let Some(name) = html_tag_name(&tag) {
+                    self.open_tag(name);
+                }
+            }
+            Event::End(tag) => {
+                if html_tag_name(&tag).is_some() {
+                    self.close_tag();
+                }
+            }
+            Event::Text(text) => self.push_text(&text),
+            Event::Code(text) => {
+                self.open_tag("code");
+                self.push_text(&text);
+                self.close_tag();
+            }
+            Event::SoftBreak | Event::HardBreak => self.push_text(" "),
+            _ => {}
+        }
+    }
+}
+
+/// Maps the handful of tags `HtmlWithLimit` knows how to open and close
+/// back to their HTML element name.
+fn html_tag_name(tag: &Tag<'_>) -> Option<&'static str> {
+    match tag {
+        Tag::Paragraph => Some("p"),
+        Tag::Emphasis => Some("em"),
+        Tag::Strong => Some("strong"),
+        Tag::Strikethrough => Some("del"),
+        Tag::CodeBlock(_) => Some("code"),
+        _ => None,
+    }
+}
+
+/// A locale-specific typography pass applied to the text of a rendered
+/// page, such as English curly quotes or French punctuation spacing.
+trait Typography {
+    /// Transforms the accumulated text of an `Event::Text` node.
+    fn convert(&mut self, text: &str) -> String;
+
+    /// Called whenever rendering crosses a block boundary (a new
+    /// paragraph, heading, etc.), so per-block state (like an open quote)
+    /// doesn't leak into the next block.
+    fn reset(&mut self) {}
+}
+
+/// The default typography: converts straight quotes to curly quotes.
+struct EnglishTypography;
+
+impl Typography for EnglishTypography {
+    fn convert(&mut self, text: &str) -> String {
+        convert_quotes_to_curly(text)
+    }
+}
+
+/// French typography: a narrow no-break space (U+202F) before `;`, `!`,
+/// `?` and around `:`, guillemets (`« »`) in place of straight double
+/// quotes, and a non-breaking space after a dialogue em-dash.
+#[derive(Default)]
+struct FrenchTypography {
+    // Straight double quotes alternate between opening and closing, since
+    // the character alone can't tell us which one we're looking at. A
+    // quoted phrase can span several `Event::Text` nodes (e.g. when it
+    // contains emphasis or a link), so this has to persist across calls
+    // rather than reset on every `convert`.
+    quote_is_open: bool,
+}
+
+impl Typography for FrenchTypography {
+    fn convert(&mut self, text: &str) -> String {
+        convert_typography_fr(text, &mut self.quote_is_open)
+    }
+
+    fn reset(&mut self) {
+        self.quote_is_open = false;
+    }
+}
+
+/// Selects the `Typography` implementation for a book config locale,
+/// defaulting to `EnglishTypography` for an unrecognised or empty locale.
+fn typography_for_locale(locale: &str) -> Box<dyn Typography> {
+    match locale {
+        "fr" => Box::new(FrenchTypography::default()),
+        _ => Box::new(EnglishTypography),
+    }
+}
+
+struct TypographyConverter {
     enabled: bool,
     convert_text: bool,
+    typography: Box<dyn Typography>,
 }
 
-impl EventQuoteConverter {
-    fn new(enabled: bool) -> Self {
-        EventQuoteConverter {
-            enabled,
-            convert_text: true,
+impl TypographyConverter {
+    fn new(locale: Option<&str>) -> Self {
+        match locale {
+            Some(locale) => TypographyConverter {
+                enabled: true,
+                convert_text: true,
+                typography: typography_for_locale(locale),
+            },
+            None => TypographyConverter {
+                enabled: false,
+                convert_text: true,
+                typography: Box::new(EnglishTypography),
+            },
         }
     }
 
@@ -151,23 +661,34 @@ impl EventQuoteConverter {
             return event;
         }
 
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => self.convert_text = false,
+            Event::End(Tag::CodeBlock(_)) => self.convert_text = true,
+            // Inline tags (emphasis, a link, ...) can split a single
+            // quoted phrase across several `Event::Text` nodes, so only
+            // a genuine block boundary should reset typography state.
+            Event::Start(tag) if !is_inline_tag(tag) => self.typography.reset(),
+            _ => {}
+        }
+
         match event {
-            Event::Start(Tag::CodeBlock(_)) => {
-                self.convert_text = false;
-                event
-            }
-            Event::End(Tag::CodeBlock(_)) => {
-                self.convert_text = true;
-                event
-            }
             Event::Text(ref text) if self.convert_text => {
-                Event::Text(CowStr::from(convert_quotes_to_curly(text)))
+                Event::Text(CowStr::from(self.typography.convert(text)))
             }
             _ => event,
         }
     }
 }
 
+/// Tags that nest *within* a block (emphasis, strong, a link, ...) rather
+/// than starting a new one, so typography state carries across them.
+fn is_inline_tag(tag: &Tag<'_>) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
 fn clean_codeblock_headers(event: Event<'_>) -> Event<'_> {
     match event {
         Event::Start(Tag::CodeBlock(ref info)) => {
@@ -211,6 +732,83 @@ fn convert_quotes_to_curly(original_text: &str) -> String {
         .collect()
 }
 
+/// Applies French typography rules to a chunk of text: a narrow no-break
+/// space before `;`, `!`, `?` and around `:`, guillemets in place of
+/// straight double quotes, and a non-breaking space after a dialogue
+/// em-dash.
+///
+/// `quote_is_open` tracks whether we're inside an open pair of straight
+/// double quotes. It's owned by the caller (`FrenchTypography`) because a
+/// single quoted phrase can be split across several calls, one per
+/// `Event::Text` node, when it contains inline markup.
+fn convert_typography_fr(original_text: &str, quote_is_open: &mut bool) -> String {
+    const NNBSP: char = '\u{202f}';
+    const NBSP: char = '\u{a0}';
+
+    let mut result = String::with_capacity(original_text.len());
+    let mut chars = original_text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            ';' | '!' | '?' => {
+                if result.ends_with(' ') {
+                    result.pop();
+                }
+                if !result.ends_with(NNBSP) {
+                    result.push(NNBSP);
+                }
+                result.push(ch);
+            }
+            ':' => {
+                if result.ends_with(' ') {
+                    result.pop();
+                }
+                if !result.ends_with(NNBSP) {
+                    result.push(NNBSP);
+                }
+                result.push(ch);
+                if chars.peek().map_or(false, |next| !next.is_whitespace()) {
+                    result.push(NNBSP);
+                }
+            }
+            '"' => {
+                if *quote_is_open {
+                    if result.ends_with(' ') {
+                        result.pop();
+                    }
+                    result.push(NBSP);
+                    result.push('»');
+                } else {
+                    result.push('«');
+                    result.push(NBSP);
+                }
+                *quote_is_open = !*quote_is_open;
+            }
+            '«' => {
+                result.push('«');
+                result.push(NBSP);
+            }
+            '»' => {
+                if result.ends_with(' ') {
+                    result.pop();
+                }
+                result.push(NBSP);
+                result.push('»');
+            }
+            '—' => {
+                result.push(ch);
+                if chars.peek() == Some(&' ') {
+                    chars.next();
+                    result.push(NBSP);
+                }
+            }
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
 /// Prints a "backtrace" of some `Error`.
 pub fn log_backtrace(e: &Error) {
     error!("Error: {}", e);
@@ -344,6 +942,79 @@ more text with spaces
         }
     }
 
+    mod typography {
+        use super::super::render_markdown_with_typography;
+
+        #[test]
+        fn it_closes_a_french_quote_interrupted_by_inline_markup() {
+            assert_eq!(
+                render_markdown_with_typography("\"Bonjour *le monde*\"", Some("fr"), ""),
+                "<p>«\u{a0}Bonjour <em>le monde</em>\u{a0}»</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_resets_quote_state_between_paragraphs() {
+            let input = "\"Bonjour\n\n\"Au revoir";
+            let expected = "<p>«\u{a0}Bonjour</p>\n<p>«\u{a0}Au revoir</p>\n";
+            assert_eq!(render_markdown_with_typography(input, Some("fr"), ""), expected);
+        }
+    }
+
+    mod render_markdown_summary {
+        use super::super::render_markdown_summary;
+
+        #[test]
+        fn it_does_not_truncate_short_input() {
+            assert_eq!(
+                render_markdown_summary("Hello *world*", 100),
+                "<p>Hello <em>world</em></p>"
+            );
+        }
+
+        #[test]
+        fn it_truncates_without_breaking_tags() {
+            assert_eq!(
+                render_markdown_summary("Hello *world*, how are you?", 8),
+                "<p>Hello <em>wo</em></p>"
+            );
+        }
+
+        #[test]
+        fn it_closes_every_open_tag() {
+            assert_eq!(
+                render_markdown_summary("**bold** and *italic*", 4),
+                "<p><strong>bold</strong></p>"
+            );
+        }
+    }
+
+    mod offset_heading {
+        use super::super::{offset_heading, Event, Tag};
+
+        #[test]
+        fn it_shifts_heading_levels_down() {
+            assert_eq!(
+                offset_heading(Event::Start(Tag::Heading(1)), 2),
+                Event::Start(Tag::Heading(3))
+            );
+        }
+
+        #[test]
+        fn it_clamps_at_h6() {
+            assert_eq!(
+                offset_heading(Event::Start(Tag::Heading(5)), 4),
+                Event::Start(Tag::Heading(6))
+            );
+        }
+
+        #[test]
+        fn it_leaves_other_events_alone() {
+            let event = Event::Text("hello".into());
+            assert_eq!(offset_heading(event.clone(), 3), event);
+        }
+    }
+
     mod html_munging {
         use super::super::{id_from_content, normalize_id};
 
@@ -389,6 +1060,213 @@ more text with spaces
         }
     }
 
+    mod id_map {
+        use super::super::IdMap;
+
+        #[test]
+        fn it_deduplicates_repeated_headings() {
+            let mut id_map = IdMap::new();
+            assert_eq!(id_map.add("## Examples"), "examples");
+            assert_eq!(id_map.add("## Examples"), "examples-1");
+            assert_eq!(id_map.add("## Examples"), "examples-2");
+        }
+
+        #[test]
+        fn it_leaves_unique_headings_alone() {
+            let mut id_map = IdMap::new();
+            assert_eq!(id_map.add("## Examples"), "examples");
+            assert_eq!(id_map.add("## Usage"), "usage");
+        }
+
+        #[test]
+        fn it_does_not_collide_with_a_literal_suffixed_heading() {
+            let mut id_map = IdMap::new();
+            assert_eq!(id_map.add("## Foo-1"), "foo-1");
+            assert_eq!(id_map.add("## Foo"), "foo");
+            // The naive "base-count" suffix would also be "foo-1", which
+            // is already taken by the first heading above.
+            assert_eq!(id_map.add("## Foo"), "foo-2");
+        }
+
+        #[test]
+        fn it_falls_back_to_a_stable_id_for_an_empty_base() {
+            let mut id_map = IdMap::new();
+            assert_eq!(id_map.add("## 🐙"), "a");
+            assert_eq!(id_map.add("## 🦀"), "a-1");
+        }
+    }
+
+    mod link_checking {
+        use super::super::{render_markdown_checking_links, BrokenLink, LinkCollector, check_links};
+        use std::collections::{HashMap, HashSet};
+
+        #[test]
+        fn it_records_relative_link_targets() {
+            let mut collector = LinkCollector::new();
+            render_markdown_checking_links(
+                "[example](example.md#anchor)",
+                None,
+                "",
+                0,
+                Some(&mut collector),
+            );
+
+            assert_eq!(collector.targets[0].path, "example.html");
+            assert_eq!(collector.targets[0].fragment.as_deref(), Some("anchor"));
+        }
+
+        #[test]
+        fn it_does_not_record_external_links() {
+            let mut collector = LinkCollector::new();
+            render_markdown_checking_links(
+                "[example](https://www.rust-lang.org/)",
+                None,
+                "",
+                0,
+                Some(&mut collector),
+            );
+
+            assert!(collector.targets.is_empty());
+        }
+
+        #[test]
+        fn it_does_not_record_same_page_anchors() {
+            let mut collector = LinkCollector::new();
+            render_markdown_checking_links(
+                "[see below](#section)",
+                None,
+                "",
+                0,
+                Some(&mut collector),
+            );
+
+            assert!(collector.targets.is_empty());
+        }
+
+        #[test]
+        fn it_does_not_record_non_markdown_relative_targets() {
+            let mut collector = LinkCollector::new();
+            render_markdown_checking_links(
+                "![diagram](img.png) [download](archive.zip)",
+                None,
+                "",
+                0,
+                Some(&mut collector),
+            );
+
+            assert!(collector.targets.is_empty());
+        }
+
+        #[test]
+        fn it_flags_links_to_missing_pages_and_fragments() {
+            let mut collector = LinkCollector::new();
+            render_markdown_checking_links(
+                "[a](a.md) [b](b.md#missing)",
+                None,
+                "",
+                0,
+                Some(&mut collector),
+            );
+
+            let pages: HashSet<String> = vec!["b.html".to_string()].into_iter().collect();
+            let mut anchors = HashMap::new();
+            anchors.insert(
+                "b.html".to_string(),
+                vec!["present".to_string()].into_iter().collect::<HashSet<_>>(),
+            );
+
+            let links: Vec<_> = collector
+                .targets
+                .iter()
+                .map(|target| ("page.html", target))
+                .collect();
+            let broken = check_links(links, &pages, &anchors);
+
+            assert_eq!(
+                broken,
+                vec![
+                    BrokenLink::MissingPage {
+                        from: "page.html".to_string(),
+                        target: "a.html".to_string(),
+                    },
+                    BrokenLink::MissingFragment {
+                        from: "page.html".to_string(),
+                        target: "b.html".to_string(),
+                        fragment: "missing".to_string(),
+                    },
+                ]
+            );
+        }
+    }
+
+    mod toc_builder {
+        use super::super::{Event, IdMap, Tag, TocBuilder, TocItem};
+
+        fn heading(builder: &mut TocBuilder<'_>, level: u32, text: &str) {
+            builder.consume(&Event::Start(Tag::Heading(level)));
+            builder.consume(&Event::Text(text.into()));
+            builder.consume(&Event::End(Tag::Heading(level)));
+        }
+
+        #[test]
+        fn it_nests_siblings_under_their_parent() {
+            let mut id_map = IdMap::new();
+            let mut builder = TocBuilder::new(&mut id_map);
+            heading(&mut builder, 1, "A");
+            heading(&mut builder, 2, "B");
+            heading(&mut builder, 2, "C");
+            let toc = builder.into_toc();
+
+            assert_eq!(
+                toc.items,
+                vec![TocItem {
+                    level: 1,
+                    name: "A".into(),
+                    id: "a".into(),
+                    children: vec![
+                        TocItem { level: 2, name: "B".into(), id: "b".into(), children: Vec::new() },
+                        TocItem { level: 2, name: "C".into(), id: "c".into(), children: Vec::new() },
+                    ],
+                }]
+            );
+        }
+
+        #[test]
+        fn it_synthesizes_skipped_levels_instead_of_panicking() {
+            let mut id_map = IdMap::new();
+            let mut builder = TocBuilder::new(&mut id_map);
+            heading(&mut builder, 1, "A");
+            heading(&mut builder, 4, "D");
+            let toc = builder.into_toc();
+
+            assert_eq!(
+                toc.items,
+                vec![TocItem {
+                    level: 1,
+                    name: "A".into(),
+                    id: "a".into(),
+                    children: vec![TocItem {
+                        level: 4,
+                        name: "D".into(),
+                        id: "d".into(),
+                        children: Vec::new(),
+                    }],
+                }]
+            );
+        }
+
+        #[test]
+        fn it_reuses_the_id_map_so_anchors_match() {
+            let mut id_map = IdMap::new();
+            id_map.add("Examples");
+            let mut builder = TocBuilder::new(&mut id_map);
+            heading(&mut builder, 1, "Examples");
+            let toc = builder.into_toc();
+
+            assert_eq!(toc.items[0].id, "examples-1");
+        }
+    }
+
     mod convert_quotes_to_curly {
         use super::super::convert_quotes_to_curly;
 
@@ -413,4 +1291,44 @@ more text with spaces
             assert_eq!(convert_quotes_to_curly("\t'one'"), "\t‘one’");
         }
     }
+
+    mod convert_typography_fr {
+        use super::super::convert_typography_fr;
+
+        fn convert(text: &str) -> String {
+            convert_typography_fr(text, &mut false)
+        }
+
+        #[test]
+        fn it_adds_narrow_no_break_spaces_before_punctuation() {
+            assert_eq!(convert("Vraiment ? Oui !"), "Vraiment\u{202f}? Oui\u{202f}!");
+            assert_eq!(convert("Ex: ceci"), "Ex\u{202f}: ceci");
+            assert_eq!(convert("Ex:ceci"), "Ex\u{202f}:\u{202f}ceci");
+        }
+
+        #[test]
+        fn it_converts_double_quotes_to_guillemets() {
+            assert_eq!(convert("\"Bonjour\""), "«\u{a0}Bonjour\u{a0}»");
+        }
+
+        #[test]
+        fn it_adds_a_non_break_space_after_a_dialogue_dash() {
+            assert_eq!(convert("— Bonjour"), "—\u{a0}Bonjour");
+        }
+
+        #[test]
+        fn it_carries_an_open_quote_across_calls() {
+            let mut quote_is_open = false;
+            assert_eq!(
+                convert_typography_fr("\"Bonjour ", &mut quote_is_open),
+                "«\u{a0}Bonjour "
+            );
+            assert!(quote_is_open);
+            assert_eq!(
+                convert_typography_fr(" le monde\"", &mut quote_is_open),
+                " le monde\u{a0}»"
+            );
+            assert!(!quote_is_open);
+        }
+    }
 }